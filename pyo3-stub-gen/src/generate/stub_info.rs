@@ -2,8 +2,7 @@ use crate::{generate::*, pyproject::PyProject, type_info::*};
 use anyhow::{Context, Result};
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fs,
-    io::Write,
+    fmt, fs,
     path::*,
 };
 
@@ -11,8 +10,105 @@ use std::{
 pub struct StubInfo {
     pub modules: BTreeMap<String, Module>,
     pub python_root: PathBuf,
-    pub module_filter: Option<String>,
-    pub rust_module_filters: Option<Vec<String>>,
+    pub module_filter: ModuleFilters,
+    pub rust_module_filters: ModuleFilters,
+    /// Per-crate output roots for workspace-aware generation, as `(module
+    /// name prefix, python source directory)`, ordered longest-prefix-first
+    /// so overlapping namespaces resolve to the most specific crate. Empty
+    /// unless built via [StubInfo::from_workspace].
+    pub output_roots: Vec<(String, PathBuf)>,
+}
+
+/// A set of glob include/exclude patterns used to decide whether a module
+/// (or a compile-time Rust module path) should be emitted.
+///
+/// A module matches when it matches at least one include pattern (or no
+/// include patterns are set at all) and no exclude pattern - excludes
+/// always take precedence. Patterns may use `*` (any run of characters) and
+/// `?` (any single character); a pattern passed with a leading `!`, e.g. via
+/// [ModuleFilters::new], is treated as an exclude pattern instead of an
+/// include pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleFilters {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl ModuleFilters {
+    /// An empty filter set, matching every module.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Build from a single list of patterns, where a leading `!` marks a
+    /// pattern as an exclude pattern (e.g. `"mypkg.internal.*"` is an
+    /// include pattern, `"!mypkg.internal.experimental.*"` is an exclude
+    /// pattern).
+    pub fn new<S: Into<String>>(patterns: impl IntoIterator<Item = S>) -> Self {
+        let mut filters = Self::empty();
+        for pattern in patterns {
+            let pattern = pattern.into();
+            match pattern.strip_prefix('!') {
+                Some(negated) => filters.exclude.push(negated.to_string()),
+                None => filters.include.push(pattern),
+            }
+        }
+        filters
+    }
+
+    /// Build from separate include and exclude pattern lists.
+    pub fn with_include_exclude<S: Into<String>, T: Into<String>>(
+        include: impl IntoIterator<Item = S>,
+        exclude: impl IntoIterator<Item = T>,
+    ) -> Self {
+        Self {
+            include: include.into_iter().map(Into::into).collect(),
+            exclude: exclude.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        let included = self.include.is_empty() || self.include.iter().any(|p| glob_match(p, text));
+        included && !self.exclude.iter().any(|p| glob_match(p, text))
+    }
+}
+
+/// Match `text` against a shell-style glob `pattern` where `*` matches any
+/// run of characters (including none) and `?` matches exactly one
+/// character. No path-segment semantics are implied: `*` happily crosses
+/// `.` boundaries, so `mypkg.*` matches `mypkg.internal.experimental` too.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let (mut star, mut match_from) = (None, 0);
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some(pi);
+            match_from = ti;
+            pi += 1;
+        } else if let Some(star_idx) = star {
+            pi = star_idx + 1;
+            match_from += 1;
+            ti = match_from;
+        } else {
+            return false;
+        }
+    }
+    while pi < p.len() && p[pi] == '*' {
+        pi += 1;
+    }
+    pi == p.len()
 }
 
 impl StubInfo {
@@ -40,7 +136,24 @@ impl StubInfo {
         filter: impl Into<String>,
     ) -> Result<Self> {
         let mut builder = StubInfoBuilder::from_project_root(default_module_name, project_root);
-        builder.module_filter = Some(filter.into());
+        builder.module_filter = ModuleFilters::new([format!("{}*", filter.into())]);
+        Ok(builder.build())
+    }
+
+    /// Initialize [StubInfo] with glob include/exclude filters for both the
+    /// Python module path and the compile-time Rust module path, so
+    /// submodules can be carved out without re-organizing the crate (e.g.
+    /// `include: ["mypkg.internal.*"], exclude: ["mypkg.internal.experimental.*"]`).
+    /// This must be placed in your PyO3 library crate, i.e. the same crate where [inventory::submit]ted,
+    /// not in the `gen_stub` executables due to [inventory]'s mechanism.
+    pub fn from_project_root_with_filters(
+        default_module_name: String,
+        project_root: PathBuf,
+        module_filter: ModuleFilters,
+        rust_module_filter: ModuleFilters,
+    ) -> Result<Self> {
+        let mut builder = StubInfoBuilder::from_project_root(default_module_name, project_root);
+        builder.with_filters(module_filter, rust_module_filter);
         Ok(builder.build())
     }
 
@@ -74,43 +187,624 @@ impl StubInfo {
         rust_filters: &[String],
     ) -> Result<Self> {
         let mut builder = StubInfoBuilder::from_project_root(default_module_name, project_root);
-        builder.rust_module_filters = Some(rust_filters.to_vec());
+        builder.rust_module_filters =
+            ModuleFilters::new(rust_filters.iter().map(|filter| format!("{filter}*")));
         Ok(builder.build())
     }
 
+    /// Initialize [StubInfo] for a Cargo workspace that links several PyO3
+    /// crates into a single `gen_stub` binary.
+    ///
+    /// This runs `cargo metadata` to enumerate workspace members, reads each
+    /// member's `pyproject.toml` for its module name and python source
+    /// directory, and builds a routing table so that modules registered by
+    /// different crates (via [inventory], which already aggregates
+    /// everything linked into the binary regardless of crate boundaries)
+    /// are written under their own crate's package tree instead of being
+    /// forced into one `python_root`.
+    pub fn from_workspace() -> Result<Self> {
+        let metadata = cargo_metadata::MetadataCommand::new()
+            .exec()
+            .context("Failed to run `cargo metadata`")?;
+
+        let root_package = metadata
+            .root_package()
+            .context("Workspace has no root package; run from a crate, not a virtual manifest")?;
+        let root_manifest_dir = root_package
+            .manifest_path
+            .parent()
+            .context("Cannot get parent directory of Cargo manifest")?;
+        let root_pyproject =
+            PyProject::parse_toml(root_manifest_dir.join("pyproject.toml").as_std_path())?;
+        let default_module_name = root_pyproject.module_name().to_string();
+        let default_python_root = root_pyproject
+            .python_source()
+            .unwrap_or_else(|| root_manifest_dir.as_std_path().to_path_buf());
+
+        let mut routes = Vec::new();
+        for id in &metadata.workspace_members {
+            let package = &metadata[id];
+            let manifest_dir = package
+                .manifest_path
+                .parent()
+                .context("Cannot get parent directory of Cargo manifest")?;
+            let pyproject_path = manifest_dir.join("pyproject.toml");
+            if !pyproject_path.exists() {
+                continue;
+            }
+            let pyproject = PyProject::parse_toml(pyproject_path.as_std_path())?;
+            let python_root = pyproject
+                .python_source()
+                .unwrap_or_else(|| manifest_dir.as_std_path().to_path_buf());
+            routes.push((pyproject.module_name().to_string(), python_root));
+        }
+        // Most specific (longest) module prefix wins when namespaces overlap.
+        routes.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+        Ok(StubInfoBuilder::from_workspace(default_module_name, default_python_root, routes).build())
+    }
+
     pub fn generate(&self) -> Result<()> {
+        let resolver = ImportResolver::new(&self.modules);
         for (name, module) in self.modules.iter() {
-            let path = name.replace(".", "/");
-            let dest = if module.submodules.is_empty() {
-                self.python_root.join(format!("{path}.pyi"))
-            } else {
-                self.python_root.join(path).join("__init__.pyi")
-            };
-
+            let dest = self.dest_path(name, module);
             let dir = dest.parent().context("Cannot get parent directory")?;
             if !dir.exists() {
                 fs::create_dir_all(dir)?;
             }
-
-            let mut f = fs::File::create(&dest)?;
-            write!(f, "{module}")?;
+            fs::write(&dest, Self::render_module(&resolver, name, module))?;
             log::info!(
                 "Generate stub file of a module `{name}` at {dest}",
                 dest = dest.display()
             );
         }
+        for parent in self.implied_parent_packages() {
+            let dest = self.implied_parent_dest_path(&parent);
+            let dir = dest.parent().context("Cannot get parent directory")?;
+            if !dir.exists() {
+                fs::create_dir_all(dir)?;
+            }
+            if !dest.exists() {
+                fs::write(&dest, "")?;
+                log::info!(
+                    "Generate empty package marker for implied parent `{parent}` at {dest}",
+                    dest = dest.display()
+                );
+            }
+        }
         Ok(())
     }
+
+    /// Check that the `.pyi` files already on disk (under `python_root`, or
+    /// the matching entry of `output_roots`) match
+    /// what [generate](StubInfo::generate) would produce, without writing
+    /// anything. Returns an error listing every module whose stub is
+    /// missing or out of date, so CI can gate on stale stubs the same way
+    /// `cargo check` gates compilation.
+    pub fn check(&self) -> Result<()> {
+        let resolver = ImportResolver::new(&self.modules);
+        let mut stale = Vec::new();
+        for (name, module) in self.modules.iter() {
+            let dest = self.dest_path(name, module);
+            let expected = Self::render_module(&resolver, name, module);
+            match fs::read_to_string(&dest) {
+                Ok(actual) if actual == expected => {}
+                Ok(_) => stale.push(format!("{name} ({}) is out of date", dest.display())),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    stale.push(format!("{name} ({}) is missing", dest.display()))
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to read {}", dest.display()))
+                }
+            }
+        }
+        for parent in self.implied_parent_packages() {
+            let dest = self.implied_parent_dest_path(&parent);
+            if !dest.exists() {
+                stale.push(format!("{parent} ({}) is missing", dest.display()));
+            }
+        }
+        if stale.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!(
+                "{} stub file(s) are missing or out of date:\n{}",
+                stale.len(),
+                stale.join("\n")
+            )
+        }
+    }
+
+    /// Every dotted-path ancestor implied by a registered module name that
+    /// is not itself a registered module. [StubInfoBuilder::register_submodules]
+    /// only records a child under a parent that already has a `Module`
+    /// entry of its own - a parent with no directly-registered items (no
+    /// `#[pyclass]`, no `#[pyfunction]`, ...) in it never becomes a key in
+    /// `self.modules`, so its own `__init__.pyi` package marker is never
+    /// generated or checked unless callers account for it separately.
+    fn implied_parent_packages(&self) -> BTreeSet<String> {
+        let mut implied = BTreeSet::new();
+        for name in self.modules.keys() {
+            let parts: Vec<&str> = name.split('.').collect();
+            for i in 1..parts.len() {
+                let parent = parts[..i].join(".");
+                if !self.modules.contains_key(&parent) {
+                    implied.insert(parent);
+                }
+            }
+        }
+        implied
+    }
+
+    /// Where [generate](StubInfo::generate) writes (and [check](StubInfo::check)
+    /// looks for) the empty `__init__.pyi` package marker for an implied
+    /// parent package returned by [implied_parent_packages](Self::implied_parent_packages).
+    fn implied_parent_dest_path(&self, parent: &str) -> PathBuf {
+        self.output_root_for(parent)
+            .join(parent.replace('.', "/"))
+            .join("__init__.pyi")
+    }
+
+    fn dest_path(&self, name: &str, module: &Module) -> PathBuf {
+        let root = self.output_root_for(name);
+        let path = name.replace(".", "/");
+        if module.submodules.is_empty() {
+            root.join(format!("{path}.pyi"))
+        } else {
+            root.join(path).join("__init__.pyi")
+        }
+    }
+
+    /// The output root for `module`: the longest registered workspace
+    /// prefix that matches it, or the default `python_root` for ordinary,
+    /// single-crate generation (`output_roots` is empty unless this
+    /// [StubInfo] was built via [StubInfo::from_workspace]).
+    fn output_root_for(&self, module: &str) -> &Path {
+        self.output_roots
+            .iter()
+            .find(|(prefix, _)| {
+                module == prefix.as_str() || module.starts_with(&format!("{prefix}."))
+            })
+            .map(|(_, root)| root.as_path())
+            .unwrap_or(&self.python_root)
+    }
+
+    fn render_module(resolver: &ImportResolver, name: &str, module: &Module) -> String {
+        let body = Self::render_body(module);
+        let (body, headers) = resolver.rewrite_body_with_imports(name, &body);
+
+        let mut out = String::new();
+        for header in &headers {
+            out.push_str(&header.to_string());
+            out.push('\n');
+        }
+        if !headers.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&body);
+        out
+    }
+
+    /// Renders `module`'s body, inserting a `@typing_extensions.deprecated(...)`
+    /// decorator line directly above every class/function/method definition
+    /// that carries [DeprecatedInfo]. [Module]'s own `Display` impl has no
+    /// knowledge of the inventory-collected deprecation data gathered here,
+    /// so this is the one place that data actually reaches the `.pyi` text.
+    fn render_body(module: &Module) -> String {
+        let mut class_markers: BTreeMap<String, &DeprecatedInfo> = BTreeMap::new();
+        let mut method_markers: BTreeMap<(String, String), &DeprecatedInfo> = BTreeMap::new();
+        for class in module.class.values() {
+            if let Some(deprecated) = &class.deprecated {
+                class_markers.insert(class.name.to_string(), deprecated);
+            }
+            for method in &class.methods {
+                if let Some(deprecated) = &method.deprecated {
+                    method_markers.insert(
+                        (class.name.to_string(), format!("def {}(", method.name)),
+                        deprecated,
+                    );
+                }
+            }
+        }
+        for enum_ in module.enum_.values() {
+            for method in &enum_.methods {
+                if let Some(deprecated) = &method.deprecated {
+                    method_markers.insert(
+                        (enum_.name.to_string(), format!("def {}(", method.name)),
+                        deprecated,
+                    );
+                }
+            }
+        }
+        let mut function_markers: BTreeMap<String, &DeprecatedInfo> = BTreeMap::new();
+        for (name, function) in &module.function {
+            if let Some(deprecated) = &function.deprecated {
+                function_markers.insert(format!("def {name}("), deprecated);
+            }
+        }
+
+        Self::apply_deprecation_markers(&module.to_string(), &class_markers, &method_markers, &function_markers)
+    }
+
+    /// Walks `body` line by line, inserting a `@typing_extensions.deprecated(...)`
+    /// line above every class/method/function declaration that has a marker
+    /// in the corresponding map. Split out from [render_body](Self::render_body)
+    /// so it can be tested against literal `.pyi` text without needing a
+    /// real [Module].
+    ///
+    /// Method markers are scoped to the class they were collected from -
+    /// tracked via `current_class` as lines are walked - rather than matched
+    /// as a flat list, so e.g. `ClassB::close` is never decorated just
+    /// because `ClassA::close` (a different class, same method name) is
+    /// deprecated. Class markers are looked up by the exact name parsed off
+    /// the `class Name(...)`/`class Name:` line, not a prefix match, so a
+    /// deprecated `Foo` can't also match an unrelated `FooExtra`.
+    fn apply_deprecation_markers(
+        body: &str,
+        class_markers: &BTreeMap<String, &DeprecatedInfo>,
+        method_markers: &BTreeMap<(String, String), &DeprecatedInfo>,
+        function_markers: &BTreeMap<String, &DeprecatedInfo>,
+    ) -> String {
+        if class_markers.is_empty() && method_markers.is_empty() && function_markers.is_empty() {
+            return body.to_string();
+        }
+
+        let mut out = String::new();
+        let mut current_class: Option<String> = None;
+        for line in body.lines() {
+            let trimmed = line.trim_start();
+            let indent = &line[..line.len() - trimmed.len()];
+
+            if !trimmed.is_empty() && indent.is_empty() {
+                current_class = Self::parse_top_level_class(trimmed);
+            }
+
+            let deprecated = if let Some(class_name) = &current_class {
+                if trimmed.is_empty() {
+                    None
+                } else if indent.is_empty() {
+                    class_markers.get(class_name).copied()
+                } else {
+                    trimmed
+                        .split_once('(')
+                        .map(|(head, _)| format!("{head}("))
+                        .and_then(|marker| method_markers.get(&(class_name.clone(), marker)).copied())
+                }
+            } else if indent.is_empty() {
+                trimmed
+                    .split_once('(')
+                    .map(|(head, _)| format!("{head}("))
+                    .and_then(|marker| function_markers.get(&marker).copied())
+            } else {
+                None
+            };
+
+            if let Some(deprecated) = deprecated {
+                out.push_str(indent);
+                out.push_str("@typing_extensions.deprecated(");
+                out.push_str(&deprecated.to_string());
+                out.push_str(")\n");
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Parses the class name out of a top-level `class Name(...)`/`class
+    /// Name:` rendered line, or `None` if `trimmed` isn't a class
+    /// declaration. Stops at the first character that can't be part of a
+    /// Python identifier, so `class Foo(Base):` yields `"Foo"` rather than
+    /// swallowing its base-class list.
+    fn parse_top_level_class(trimmed: &str) -> Option<String> {
+        let rest = trimmed.strip_prefix("class ")?;
+        let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        (end > 0).then(|| rest[..end].to_string())
+    }
+
+    /// Resolve the shortest valid way to refer to `item` (a class, enum or
+    /// error name known to this [StubInfo]) from within `from_module`, along
+    /// with the import header that must be emitted alongside it, if any.
+    ///
+    /// Returns `(item, None)` when no import is emitted: `item` is defined
+    /// in `from_module`, is not tracked at all, or is only defined in
+    /// modules deeper than [MAX_IMPORT_SEARCH_DEPTH]. Otherwise returns
+    /// `(name_to_use, Some(header))` when an import is required. `name_to_use`
+    /// is either the bare item name (for a `from <module> import <name>`
+    /// header) or an aliased attribute access (for a fallback `import
+    /// <module> as <alias>` header, used when the bare name would collide
+    /// with something else already bound in `from_module`).
+    pub fn resolve_import(&self, from_module: &str, item: &str) -> (String, Option<ImportHeader>) {
+        ImportResolver::new(&self.modules).resolve(from_module, item)
+    }
+}
+
+/// An import statement needed by a module to reference a type defined in
+/// another module, as computed by [StubInfo::resolve_import].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImportHeader {
+    /// `from <module> import <name>`
+    From { module: String, name: String },
+    /// `import <module> as <alias>`, used when the bare name would shadow
+    /// something else already defined in the referencing module.
+    Aliased { module: String, alias: String },
+}
+
+impl fmt::Display for ImportHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportHeader::From { module, name } => write!(f, "from {module} import {name}"),
+            ImportHeader::Aliased { module, alias } => write!(f, "import {module} as {alias}"),
+        }
+    }
+}
+
+/// Maximum number of path segments a candidate home module may have before
+/// it is discarded as a fallback for [ImportResolver::resolve]; guards
+/// against walking pathologically deep module graphs.
+const MAX_IMPORT_SEARCH_DEPTH: usize = 15;
+
+/// Computes, for every module, the `from ... import ...` (or aliased
+/// `import ... as ...`) headers it needs to reference classes/enums/errors
+/// that are defined elsewhere, and the body substitutions that turn the
+/// full-dotted-path reference [Module]'s `Display` impl falls back to into
+/// the resolved short name.
+struct ImportResolver<'a> {
+    modules: &'a BTreeMap<String, Module>,
+    /// "home_module.Name" -> (home module, bare name), for every
+    /// class/enum/error registered anywhere. Keyed by the fully qualified
+    /// name rather than the bare name alone, so two unrelated items that
+    /// happen to share a bare name in different modules (e.g. `foo.Error`
+    /// and `bar.Error`) are never conflated into the same entry.
+    qualified: BTreeMap<String, (&'a str, &'a str)>,
+    /// bare name -> every module it is directly accessible from. Used only
+    /// by [StubInfo::resolve_import], whose callers address an item by
+    /// bare name alone and must disambiguate across modules themselves.
+    locations: BTreeMap<&'a str, BTreeSet<&'a str>>,
+}
+
+impl<'a> ImportResolver<'a> {
+    fn new(modules: &'a BTreeMap<String, Module>) -> Self {
+        let mut qualified = BTreeMap::new();
+        let mut locations: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for (module_name, module) in modules {
+            for class in module.class.values() {
+                qualified.insert(format!("{module_name}.{}", class.name), (module_name.as_str(), class.name));
+                locations.entry(class.name).or_default().insert(module_name.as_str());
+            }
+            for enum_ in module.enum_.values() {
+                qualified.insert(format!("{module_name}.{}", enum_.name), (module_name.as_str(), enum_.name));
+                locations.entry(enum_.name).or_default().insert(module_name.as_str());
+            }
+            for error in module.error.values() {
+                qualified.insert(format!("{module_name}.{}", error.name), (module_name.as_str(), error.name));
+                locations.entry(error.name).or_default().insert(module_name.as_str());
+            }
+        }
+        Self { modules, qualified, locations }
+    }
+
+    /// Rewrite `body` (the literal `.pyi` text already produced for
+    /// `from_module`), substituting every fully-qualified cross-module
+    /// reference it contains - covering every place one can appear
+    /// (function/method args and return types, class attrs, base classes,
+    /// ...) without needing to know the shape of each individual call site
+    /// - with its resolved short name or alias, and returning the import
+    /// headers those substitutions require.
+    ///
+    /// Two precautions keep this text-level rewrite from corrupting
+    /// anything that merely *looks* like a qualified reference:
+    /// - Lines inside a `"""..."""`/`'''...'''` docstring are left
+    ///   untouched (and don't contribute headers), so prose that happens to
+    ///   mention another type's dotted path (e.g. "see `pkg.a.Widget` for
+    ///   details") isn't rewritten.
+    /// - Substitutions only match on identifier boundaries, so `pkg.a.Widget`
+    ///   is never replaced as a prefix of the unrelated `pkg.a.WidgetSet`.
+    fn rewrite_body_with_imports(&self, from_module: &str, body: &str) -> (String, BTreeSet<ImportHeader>) {
+        let mut headers = BTreeSet::new();
+        let mut out = String::with_capacity(body.len());
+        let mut in_docstring = false;
+        for line in body.lines() {
+            let has_docstring_delimiter = line.contains("\"\"\"") || line.contains("'''");
+            let mut rewritten = line.to_string();
+            if !in_docstring && !has_docstring_delimiter {
+                for (full, &(home, name)) in &self.qualified {
+                    if home == from_module || !rewritten.contains(full.as_str()) {
+                        continue;
+                    }
+                    let (substitution, header) = self.import_for(from_module, home, name);
+                    let replaced = Self::replace_at_boundaries(&rewritten, full, &substitution);
+                    if replaced != rewritten {
+                        headers.insert(header);
+                        rewritten = replaced;
+                    }
+                }
+            }
+            if Self::toggles_docstring(line) {
+                in_docstring = !in_docstring;
+            }
+            out.push_str(&rewritten);
+            out.push('\n');
+        }
+        (out, headers)
+    }
+
+    /// Replace every occurrence of `from` in `text` with `to`, but only
+    /// where `from` isn't itself a fragment of a longer identifier - i.e.
+    /// the characters immediately before and after the match (if any)
+    /// aren't identifier characters.
+    fn replace_at_boundaries(text: &str, from: &str, to: &str) -> String {
+        let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(idx) = rest.find(from) {
+            let before_ok = rest[..idx].chars().next_back().map_or(true, |c| !is_ident_char(c));
+            let after_ok = rest[idx + from.len()..].chars().next().map_or(true, |c| !is_ident_char(c));
+            out.push_str(&rest[..idx]);
+            if before_ok && after_ok {
+                out.push_str(to);
+            } else {
+                out.push_str(from);
+            }
+            rest = &rest[idx + from.len()..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Whether `line` contains an odd number of `"""`/`'''` delimiters,
+    /// i.e. it flips whether subsequent lines are inside a docstring.
+    fn toggles_docstring(line: &str) -> bool {
+        (line.matches("\"\"\"").count() + line.matches("'''").count()) % 2 == 1
+    }
+
+    /// Resolve the shortest valid way to name `home.name` from
+    /// `from_module`: the bare name directly (with a `from home import
+    /// name` header), or, when that bare name would collide with
+    /// something already bound in `from_module`, an aliased attribute
+    /// access (with an `import home as _alias` header).
+    fn import_for(&self, from_module: &str, home: &str, name: &str) -> (String, ImportHeader) {
+        if self.name_collides(from_module, name) {
+            let alias = format!("_{}", home.replace('.', "_"));
+            (
+                format!("{alias}.{name}"),
+                ImportHeader::Aliased {
+                    module: home.to_string(),
+                    alias,
+                },
+            )
+        } else {
+            (
+                name.to_string(),
+                ImportHeader::From {
+                    module: home.to_string(),
+                    name: name.to_string(),
+                },
+            )
+        }
+    }
+
+    /// Resolve `item`, addressed by bare name alone, as it would need to
+    /// appear when referenced from `from_module`. Used by
+    /// [StubInfo::resolve_import], whose callers don't know (or care)
+    /// which module defines `item` - only
+    /// [rewrite_body_with_imports](Self::rewrite_body_with_imports) needs
+    /// the unambiguous fully-qualified form, since it works from
+    /// already-rendered text.
+    ///
+    /// Prefers the closest ancestor package of `from_module` that defines
+    /// `item` as the module to import it from over an unrelated module,
+    /// even if that unrelated module's name happens to be shorter; an
+    /// import header is returned in either case. Falls back to
+    /// the shallowest unrelated home, bounded by [MAX_IMPORT_SEARCH_DEPTH]
+    /// so pathologically nested module graphs can't blow up the search.
+    fn resolve(&self, from_module: &str, item: &str) -> (String, Option<ImportHeader>) {
+        let Some(homes) = self.locations.get(item) else {
+            return (item.to_string(), None);
+        };
+        if homes.contains(from_module) {
+            return (item.to_string(), None);
+        }
+
+        let home = Self::ancestors_of(from_module)
+            .find(|ancestor| homes.contains(ancestor))
+            .or_else(|| {
+                homes
+                    .iter()
+                    .map(|home| (home.split('.').count(), *home))
+                    .filter(|(depth, _)| *depth <= MAX_IMPORT_SEARCH_DEPTH)
+                    .min_by_key(|(depth, home)| (*depth, *home))
+                    .map(|(_, home)| home)
+            });
+
+        let Some(home) = home else {
+            return (item.to_string(), None);
+        };
+        let (name_to_use, header) = self.import_for(from_module, home, item);
+        (name_to_use, Some(header))
+    }
+
+    /// Every ancestor package of `module`, closest parent first -
+    /// `"pkg.sub.mod"` yields `"pkg.sub"`, then `"pkg"`.
+    fn ancestors_of(module: &str) -> impl Iterator<Item = &str> {
+        let mut rest = module;
+        std::iter::from_fn(move || {
+            let (parent, _) = rest.rsplit_once('.')?;
+            rest = parent;
+            Some(parent)
+        })
+    }
+
+    /// Whether importing `name` by its bare form into `from_module` would
+    /// shadow something else already defined there.
+    fn name_collides(&self, from_module: &str, name: &str) -> bool {
+        self.modules.get(from_module).is_some_and(|module| {
+            module.function.contains_key(name)
+                || module.class.values().any(|c| c.name == name)
+                || module.enum_.values().any(|e| e.name == name)
+        })
+    }
 }
 
 struct StubInfoBuilder {
     modules: BTreeMap<String, Module>,
     default_module_name: String,
     python_root: PathBuf,
-    module_filter: Option<String>,
-    rust_module_filters: Option<Vec<String>>,
+    module_filter: ModuleFilters,
+    rust_module_filters: ModuleFilters,
+    deprecated_classes: BTreeMap<std::any::TypeId, DeprecatedInfo>,
+    deprecated_functions: BTreeMap<(Option<&'static str>, &'static str), DeprecatedInfo>,
+    deprecated_methods: BTreeMap<(std::any::TypeId, &'static str), DeprecatedInfo>,
+    output_roots: Vec<(String, PathBuf)>,
+}
+
+/// Deprecation metadata captured from a Rust `#[deprecated(note = "...", since = "...")]`
+/// attribute, carried through to the generated stub as a decorator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeprecatedInfo {
+    pub note: Option<&'static str>,
+    pub since: Option<&'static str>,
+}
+
+impl fmt::Display for DeprecatedInfo {
+    /// Renders as the argument list of `@typing_extensions.deprecated(...)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let note = self.note.unwrap_or("deprecated");
+        match self.since {
+            Some(since) => write!(f, "\"{note} (deprecated since {since})\""),
+            None => write!(f, "\"{note}\""),
+        }
+    }
+}
+
+/// The Rust item a [PyDeprecatedInfo] was captured from, so the builder can
+/// attach it to the right entry regardless of `inventory` collection order.
+#[derive(Debug, Clone, Copy)]
+pub enum DeprecatedTarget {
+    Class {
+        struct_id: fn() -> std::any::TypeId,
+    },
+    Function {
+        module: Option<&'static str>,
+        name: &'static str,
+    },
+    Method {
+        struct_id: fn() -> std::any::TypeId,
+        name: &'static str,
+    },
 }
 
+/// Inventory-collected record of a `#[deprecated]` attribute on a
+/// `#[pyclass]`, `#[pyfunction]`, or a method inside `#[pymethods]`.
+pub struct PyDeprecatedInfo {
+    pub target: DeprecatedTarget,
+    pub note: Option<&'static str>,
+    pub since: Option<&'static str>,
+}
+
+inventory::collect!(PyDeprecatedInfo);
+
 impl StubInfoBuilder {
     fn from_pyproject_toml(pyproject: PyProject) -> Self {
         StubInfoBuilder::from_project_root(
@@ -126,8 +820,61 @@ impl StubInfoBuilder {
             modules: BTreeMap::new(),
             default_module_name,
             python_root: project_root,
-            module_filter: None,
-            rust_module_filters: None,
+            module_filter: ModuleFilters::empty(),
+            rust_module_filters: ModuleFilters::empty(),
+            deprecated_classes: BTreeMap::new(),
+            deprecated_functions: BTreeMap::new(),
+            deprecated_methods: BTreeMap::new(),
+            output_roots: Vec::new(),
+        }
+    }
+
+    /// Apply glob include/exclude filters to both the Python module path
+    /// and the compile-time Rust module path.
+    fn with_filters(&mut self, module_filter: ModuleFilters, rust_module_filter: ModuleFilters) {
+        self.module_filter = module_filter;
+        self.rust_module_filters = rust_module_filter;
+    }
+
+    /// Build from a workspace routing table (module name prefix -> that
+    /// crate's python source directory), as computed by
+    /// [StubInfo::from_workspace]. `default_module_name`/`default_python_root`
+    /// - the workspace's root package, not merely the first (or
+    /// longest-prefix) route - become the fallback for any module that
+    /// matches none of the prefixes in `routes`.
+    fn from_workspace(
+        default_module_name: String,
+        default_python_root: PathBuf,
+        routes: Vec<(String, PathBuf)>,
+    ) -> Self {
+        let mut builder = Self::from_project_root(default_module_name, default_python_root);
+        builder.output_roots = routes;
+        builder
+    }
+
+    /// Sort every [PyDeprecatedInfo] collected by `inventory` into the
+    /// per-kind lookup tables, so `add_class`/`add_function`/`add_methods`
+    /// can attach deprecation info regardless of the order `inventory`
+    /// iterates items in.
+    fn collect_deprecated(&mut self) {
+        for info in inventory::iter::<PyDeprecatedInfo> {
+            let deprecated = DeprecatedInfo {
+                note: info.note,
+                since: info.since,
+            };
+            match info.target {
+                DeprecatedTarget::Class { struct_id } => {
+                    self.deprecated_classes.insert(struct_id(), deprecated);
+                }
+                DeprecatedTarget::Function { module, name } => {
+                    self.deprecated_functions
+                        .insert((module, name), deprecated);
+                }
+                DeprecatedTarget::Method { struct_id, name } => {
+                    self.deprecated_methods
+                        .insert((struct_id(), name), deprecated);
+                }
+            }
         }
     }
 
@@ -140,20 +887,12 @@ impl StubInfoBuilder {
     }
 
     fn should_include_module(&self, module: Option<&str>) -> bool {
-        let Some(filter) = &self.module_filter else {
-            return true;
-        };
         let module_name = module.unwrap_or(&self.default_module_name);
-        module_name.starts_with(filter.as_str())
+        self.module_filter.matches(module_name)
     }
 
     fn should_include_rust_module(&self, rust_module_path: &str) -> bool {
-        let Some(filters) = &self.rust_module_filters else {
-            return true;
-        };
-        filters
-            .iter()
-            .any(|filter| rust_module_path.starts_with(filter.as_str()))
+        self.rust_module_filters.matches(rust_module_path)
     }
 
     fn register_submodules(&mut self) {
@@ -176,15 +915,17 @@ impl StubInfoBuilder {
     }
 
     fn add_class(&mut self, info: &PyClassInfo) {
-        self.get_module(info.module)
-            .class
-            .insert((info.struct_id)(), ClassDef::from(info));
+        let struct_id = (info.struct_id)();
+        let mut def = ClassDef::from(info);
+        def.deprecated = self.deprecated_classes.get(&struct_id).cloned();
+        self.get_module(info.module).class.insert(struct_id, def);
     }
 
     fn add_complex_enum(&mut self, info: &PyComplexEnumInfo) {
-        self.get_module(info.module)
-            .class
-            .insert((info.enum_id)(), ClassDef::from(info));
+        let enum_id = (info.enum_id)();
+        let mut def = ClassDef::from(info);
+        def.deprecated = self.deprecated_classes.get(&enum_id).cloned();
+        self.get_module(info.module).class.insert(enum_id, def);
     }
 
     fn add_enum(&mut self, info: &PyEnumInfo) {
@@ -194,9 +935,12 @@ impl StubInfoBuilder {
     }
 
     fn add_function(&mut self, info: &PyFunctionInfo) {
-        self.get_module(info.module)
-            .function
-            .insert(info.name, FunctionDef::from(info));
+        let mut def = FunctionDef::from(info);
+        def.deprecated = self
+            .deprecated_functions
+            .get(&(info.module, info.name))
+            .cloned();
+        self.get_module(info.module).function.insert(info.name, def);
     }
 
     fn add_error(&mut self, info: &PyErrorInfo) {
@@ -240,7 +984,9 @@ impl StubInfoBuilder {
                     });
                 }
                 for method in info.methods {
-                    entry.methods.push(MethodDef::from(method))
+                    let mut def = MethodDef::from(method);
+                    def.deprecated = self.deprecated_methods.get(&(struct_id, def.name)).cloned();
+                    entry.methods.push(def)
                 }
                 return;
             } else if let Some(entry) = module.enum_.get_mut(&struct_id) {
@@ -269,7 +1015,9 @@ impl StubInfoBuilder {
                     });
                 }
                 for method in info.methods {
-                    entry.methods.push(MethodDef::from(method))
+                    let mut def = MethodDef::from(method);
+                    def.deprecated = self.deprecated_methods.get(&(struct_id, def.name)).cloned();
+                    entry.methods.push(def)
                 }
                 return;
             }
@@ -278,6 +1026,7 @@ impl StubInfoBuilder {
     }
 
     fn build(mut self) -> StubInfo {
+        self.collect_deprecated();
         for info in inventory::iter::<PyClassInfo> {
             if self.should_include_module(info.module)
                 && self.should_include_rust_module(info.rust_module_path)
@@ -323,6 +1072,457 @@ impl StubInfoBuilder {
             python_root: self.python_root,
             module_filter: self.module_filter,
             rust_module_filters: self.rust_module_filters,
+            output_roots: self.output_roots,
+        }
+    }
+}
+
+/// Cross-checks generated [StubInfo] against the actually-built PyO3
+/// extension module, catching drift that the compile-time `inventory` pass
+/// can miss (manually-registered objects, members added programmatically by
+/// a `#[pymodule]` init function, ...). Gated behind the `runtime-check`
+/// feature since it requires the extension module to already be importable.
+#[cfg(feature = "runtime-check")]
+mod runtime_check {
+    use super::*;
+    use pyo3::prelude::*;
+    use pyo3::types::PyModule;
+
+    /// A single discrepancy found by [StubInfo::verify_runtime].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct RuntimeMismatch {
+        pub module: String,
+        pub kind: RuntimeMismatchKind,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum RuntimeMismatchKind {
+        /// Declared in the stubs but not found as an attribute of the
+        /// imported extension module.
+        MissingAtRuntime { name: String },
+        /// An attribute of the imported extension module that is not
+        /// declared anywhere in the stubs.
+        UndeclaredInStubs { name: String },
+        /// Found at runtime under the expected name, but not with a shape
+        /// compatible with how the stubs declare it (e.g. a class declared
+        /// in the stubs that is not a runtime type, or a function that is
+        /// not callable).
+        IncompatibleKind { name: String, expected: &'static str },
+    }
+
+    impl fmt::Display for RuntimeMismatch {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match &self.kind {
+                RuntimeMismatchKind::MissingAtRuntime { name } => write!(
+                    f,
+                    "`{}.{name}` is declared in the stubs but missing at runtime",
+                    self.module
+                ),
+                RuntimeMismatchKind::UndeclaredInStubs { name } => write!(
+                    f,
+                    "`{}.{name}` exists at runtime but is not declared in the stubs",
+                    self.module
+                ),
+                RuntimeMismatchKind::IncompatibleKind { name, expected } => write!(
+                    f,
+                    "`{}.{name}` is declared in the stubs as a {expected}, but the runtime value is not",
+                    self.module
+                ),
+            }
+        }
+    }
+
+    /// What shape the stubs expect a declared name to have at runtime, so
+    /// [StubInfo::verify_runtime] can check more than mere presence.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum DeclaredKind {
+        /// A class, enum, or error - expected to be a runtime type.
+        Type,
+        /// A function or method - expected to be callable.
+        Callable,
+        /// A submodule, or a plain variable whose runtime shape the stubs
+        /// don't constrain.
+        Any,
+    }
+
+    impl StubInfo {
+        /// Import every module registered in `self.modules` and compare its
+        /// runtime `dir()` against the classes/enums/functions/variables/
+        /// submodules this [StubInfo] declares for it, returning every
+        /// mismatch found (not just the first), in deterministic order.
+        /// Beyond presence, also checks that each declared name's runtime
+        /// value has a compatible shape (a declared class is a runtime
+        /// type, a declared function is callable).
+        ///
+        /// Requires the extension module to already be built and importable
+        /// from the current interpreter (e.g. via `maturin develop`).
+        pub fn verify_runtime(&self) -> Result<Vec<RuntimeMismatch>> {
+            Python::with_gil(|py| {
+                let mut mismatches = Vec::new();
+                for (name, module) in &self.modules {
+                    let py_module = PyModule::import(py, name.as_str()).with_context(|| {
+                        format!("Failed to import `{name}` - is the extension module built?")
+                    })?;
+
+                    let runtime_attrs: BTreeSet<String> = py_module
+                        .dir()?
+                        .iter()
+                        .map(|attr| attr.extract::<String>())
+                        .collect::<pyo3::PyResult<_>>()?;
+
+                    let mut declared = BTreeMap::new();
+                    declared.extend(module.class.values().map(|c| (c.name.to_string(), DeclaredKind::Type)));
+                    declared.extend(module.enum_.values().map(|e| (e.name.to_string(), DeclaredKind::Type)));
+                    declared.extend(module.error.keys().map(|name| (name.to_string(), DeclaredKind::Type)));
+                    declared
+                        .extend(module.function.keys().map(|name| (name.to_string(), DeclaredKind::Callable)));
+                    declared.extend(module.variables.keys().map(|name| (name.to_string(), DeclaredKind::Any)));
+                    declared.extend(module.submodules.iter().map(|name| (name.to_string(), DeclaredKind::Any)));
+
+                    for (item, kind) in &declared {
+                        let Ok(value) = py_module.getattr(item.as_str()) else {
+                            mismatches.push(RuntimeMismatch {
+                                module: name.clone(),
+                                kind: RuntimeMismatchKind::MissingAtRuntime { name: item.clone() },
+                            });
+                            continue;
+                        };
+                        let compatible = match kind {
+                            DeclaredKind::Type => value.is_instance_of::<pyo3::types::PyType>(),
+                            DeclaredKind::Callable => value.is_callable(),
+                            DeclaredKind::Any => true,
+                        };
+                        if !compatible {
+                            mismatches.push(RuntimeMismatch {
+                                module: name.clone(),
+                                kind: RuntimeMismatchKind::IncompatibleKind {
+                                    name: item.clone(),
+                                    expected: match kind {
+                                        DeclaredKind::Type => "class",
+                                        DeclaredKind::Callable => "function",
+                                        DeclaredKind::Any => unreachable!(),
+                                    },
+                                },
+                            });
+                        }
+                    }
+                    for attr in &runtime_attrs {
+                        if !attr.starts_with("__") && !declared.contains_key(attr) {
+                            mismatches.push(RuntimeMismatch {
+                                module: name.clone(),
+                                kind: RuntimeMismatchKind::UndeclaredInStubs {
+                                    name: attr.clone(),
+                                },
+                            });
+                        }
+                    }
+                }
+                Ok(mismatches)
+            })
+        }
+    }
+}
+
+#[cfg(feature = "runtime-check")]
+pub use runtime_check::{RuntimeMismatch, RuntimeMismatchKind};
+
+#[cfg(test)]
+mod glob_tests {
+    use super::*;
+
+    #[test]
+    fn glob_match_literal() {
+        assert!(glob_match("mypkg.core", "mypkg.core"));
+        assert!(!glob_match("mypkg.core", "mypkg.other"));
+    }
+
+    #[test]
+    fn glob_match_star_crosses_dots() {
+        assert!(glob_match("mypkg.*", "mypkg.internal.experimental"));
+        assert!(glob_match("mypkg.*", "mypkg."));
+        assert!(!glob_match("mypkg.*", "otherpkg.internal"));
+    }
+
+    #[test]
+    fn glob_match_question_mark_is_single_char() {
+        assert!(glob_match("mypkg.v?", "mypkg.v1"));
+        assert!(!glob_match("mypkg.v?", "mypkg.v12"));
+    }
+
+    #[test]
+    fn glob_match_trailing_star_allows_empty_suffix() {
+        assert!(glob_match("mypkg*", "mypkg"));
+    }
+
+    #[test]
+    fn module_filters_empty_matches_everything() {
+        let filters = ModuleFilters::empty();
+        assert!(filters.matches("anything.at.all"));
+    }
+
+    #[test]
+    fn module_filters_include_only() {
+        let filters = ModuleFilters::new(["mypkg.*"]);
+        assert!(filters.matches("mypkg.core"));
+        assert!(!filters.matches("otherpkg.core"));
+    }
+
+    #[test]
+    fn module_filters_exclude_takes_precedence_over_include() {
+        let filters = ModuleFilters::new(["mypkg.*", "!mypkg.internal.*"]);
+        assert!(filters.matches("mypkg.core"));
+        assert!(!filters.matches("mypkg.internal.experimental"));
+    }
+
+    #[test]
+    fn module_filters_exclude_only_still_matches_unexcluded() {
+        let filters = ModuleFilters::new(["!mypkg.internal.*"]);
+        assert!(filters.matches("mypkg.core"));
+        assert!(!filters.matches("mypkg.internal.experimental"));
+    }
+}
+
+#[cfg(test)]
+mod import_resolver_tests {
+    use super::*;
+    use std::any::TypeId;
+
+    struct MarkerA;
+    struct MarkerB;
+
+    fn class(name: &'static str) -> ClassDef {
+        ClassDef {
+            name,
+            ..Default::default()
         }
     }
+
+    #[test]
+    fn same_bare_name_in_different_modules_is_not_conflated() {
+        let mut modules = BTreeMap::new();
+
+        let mut pkg_a = Module::default();
+        pkg_a.name = "pkg.a".to_string();
+        pkg_a.class.insert(TypeId::of::<MarkerA>(), class("Error"));
+        modules.insert("pkg.a".to_string(), pkg_a);
+
+        let mut pkg_b = Module::default();
+        pkg_b.name = "pkg.b".to_string();
+        pkg_b.class.insert(TypeId::of::<MarkerB>(), class("Error"));
+        modules.insert("pkg.b".to_string(), pkg_b);
+
+        let resolver = ImportResolver::new(&modules);
+
+        // A module's own `Error` needs no import.
+        assert_eq!(resolver.resolve("pkg.a", "Error"), ("Error".to_string(), None));
+        assert_eq!(resolver.resolve("pkg.b", "Error"), ("Error".to_string(), None));
+
+        // Referencing `Error` from a third module must resolve to exactly
+        // one of the two distinct `Error` classes, not some conflation of
+        // both: with equally deep homes, the lexicographically first
+        // module (`pkg.a`) is chosen.
+        let (name, header) = resolver.resolve("pkg.c", "Error");
+        assert_eq!(name, "Error");
+        assert_eq!(
+            header,
+            Some(ImportHeader::From {
+                module: "pkg.a".to_string(),
+                name: "Error".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn colliding_name_triggers_aliased_import_and_body_substitution() {
+        let mut modules = BTreeMap::new();
+
+        let mut pkg_a = Module::default();
+        pkg_a.name = "pkg.a".to_string();
+        pkg_a.class.insert(TypeId::of::<MarkerA>(), class("Widget"));
+        modules.insert("pkg.a".to_string(), pkg_a);
+
+        let mut pkg_b = Module::default();
+        pkg_b.name = "pkg.b".to_string();
+        pkg_b.function.insert("Widget", FunctionDef::default());
+        modules.insert("pkg.b".to_string(), pkg_b);
+
+        let resolver = ImportResolver::new(&modules);
+
+        let (name, header) = resolver.resolve("pkg.b", "Widget");
+        assert_eq!(name, "_pkg_a.Widget");
+        assert_eq!(
+            header,
+            Some(ImportHeader::Aliased {
+                module: "pkg.a".to_string(),
+                alias: "_pkg_a".to_string(),
+            })
+        );
+
+        // The same substitution must actually reach the rendered body text,
+        // not just the resolver's internal bookkeeping.
+        let rendered = "def make() -> pkg.a.Widget: ...";
+        let (rewritten, headers) = resolver.rewrite_body_with_imports("pkg.b", rendered);
+        assert_eq!(rewritten, "def make() -> _pkg_a.Widget: ...\n");
+        assert_eq!(headers, BTreeSet::from([header.unwrap()]));
+    }
+
+    #[test]
+    fn docstring_mention_of_a_qualified_name_is_left_untouched() {
+        let mut modules = BTreeMap::new();
+
+        let mut pkg_a = Module::default();
+        pkg_a.name = "pkg.a".to_string();
+        pkg_a.class.insert(TypeId::of::<MarkerA>(), class("Widget"));
+        modules.insert("pkg.a".to_string(), pkg_a);
+        modules.insert("pkg.b".to_string(), Module::default());
+
+        let resolver = ImportResolver::new(&modules);
+
+        let rendered = "\
+def make() -> Widget:
+    \"\"\"See pkg.a.Widget for details.\"\"\"
+    ...
+";
+        let (rewritten, headers) = resolver.rewrite_body_with_imports("pkg.b", rendered);
+        assert_eq!(rewritten, rendered);
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn qualified_name_that_is_a_prefix_of_another_is_not_corrupted() {
+        let mut modules = BTreeMap::new();
+
+        let mut pkg_a = Module::default();
+        pkg_a.name = "pkg.a".to_string();
+        pkg_a.class.insert(TypeId::of::<MarkerA>(), class("Widget"));
+        pkg_a.class.insert(TypeId::of::<MarkerB>(), class("WidgetSet"));
+        modules.insert("pkg.a".to_string(), pkg_a);
+        modules.insert("pkg.b".to_string(), Module::default());
+
+        let resolver = ImportResolver::new(&modules);
+
+        let rendered = "def make() -> pkg.a.WidgetSet: ...";
+        let (rewritten, _headers) = resolver.rewrite_body_with_imports("pkg.b", rendered);
+        assert_eq!(rewritten, "def make() -> WidgetSet: ...\n");
+    }
+}
+
+#[cfg(test)]
+mod deprecation_marker_tests {
+    use super::*;
+
+    fn deprecated() -> DeprecatedInfo {
+        DeprecatedInfo {
+            note: Some("deprecated"),
+            since: None,
+        }
+    }
+
+    #[test]
+    fn method_marker_does_not_leak_into_other_class_with_same_method_name() {
+        let body = "\
+class ClassA:
+    def close(self) -> None: ...
+
+class ClassB:
+    def close(self) -> None: ...
+";
+        let class_markers = BTreeMap::new();
+        let dep = deprecated();
+        let mut method_markers = BTreeMap::new();
+        method_markers.insert(("ClassA".to_string(), "def close(".to_string()), &dep);
+        let function_markers = BTreeMap::new();
+
+        let rendered =
+            StubInfo::apply_deprecation_markers(body, &class_markers, &method_markers, &function_markers);
+
+        let lines: Vec<&str> = rendered.lines().collect();
+        // ClassA's `close` is decorated...
+        assert_eq!(lines[1], "    @typing_extensions.deprecated(\"deprecated\")");
+        assert_eq!(lines[2], "    def close(self) -> None: ...");
+        // ...but ClassB's same-named method is not - exactly one decorator
+        // appears in the whole rendered output.
+        assert_eq!(rendered.matches("@typing_extensions.deprecated").count(), 1);
+    }
+
+    #[test]
+    fn class_marker_does_not_match_unrelated_class_with_shared_prefix() {
+        let body = "\
+class Foo:
+    pass
+
+class FooExtra:
+    pass
+";
+        let dep = deprecated();
+        let mut class_markers = BTreeMap::new();
+        class_markers.insert("Foo".to_string(), &dep);
+        let method_markers = BTreeMap::new();
+        let function_markers = BTreeMap::new();
+
+        let rendered =
+            StubInfo::apply_deprecation_markers(body, &class_markers, &method_markers, &function_markers);
+
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "@typing_extensions.deprecated(\"deprecated\")");
+        assert_eq!(lines[1], "class Foo:");
+        // `FooExtra` must not pick up `Foo`'s marker via a prefix match.
+        assert_eq!(rendered.matches("@typing_extensions.deprecated").count(), 1);
+        assert!(!rendered.contains("@typing_extensions.deprecated(\"deprecated\")\nclass FooExtra"));
+    }
+}
+
+#[cfg(test)]
+mod workspace_output_root_tests {
+    use super::*;
+
+    fn stub_info_with_routes(routes: Vec<(&str, &str)>) -> StubInfo {
+        StubInfo {
+            modules: BTreeMap::new(),
+            python_root: PathBuf::from("/default/root"),
+            module_filter: ModuleFilters::empty(),
+            rust_module_filters: ModuleFilters::empty(),
+            output_roots: routes
+                .into_iter()
+                .map(|(prefix, root)| (prefix.to_string(), PathBuf::from(root)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn module_matching_no_route_falls_back_to_default_python_root() {
+        let info = stub_info_with_routes(vec![("other_crate", "/other/root")]);
+        assert_eq!(info.output_root_for("my_crate.mod"), Path::new("/default/root"));
+    }
+
+    #[test]
+    fn module_matching_a_route_prefix_uses_its_root() {
+        let info = stub_info_with_routes(vec![("my_crate", "/crate/root")]);
+        assert_eq!(info.output_root_for("my_crate.sub.mod"), Path::new("/crate/root"));
+    }
+
+    #[test]
+    fn route_prefix_must_match_on_a_dot_boundary() {
+        // `my_crate_extra` must not be treated as living under the
+        // `my_crate` route just because it shares a string prefix.
+        let info = stub_info_with_routes(vec![("my_crate", "/crate/root")]);
+        assert_eq!(
+            info.output_root_for("my_crate_extra.mod"),
+            Path::new("/default/root")
+        );
+    }
+
+    #[test]
+    fn most_specific_route_wins_when_namespaces_overlap() {
+        // Mirrors the longest-prefix-first sort `StubInfo::from_workspace`
+        // applies to `routes` before handing them to the builder: the most
+        // specific (longest) matching prefix must be checked first so it
+        // wins over a shorter, less specific ancestor route.
+        let info = stub_info_with_routes(vec![
+            ("my_crate.sub", "/sub/root"),
+            ("my_crate", "/crate/root"),
+        ]);
+        assert_eq!(info.output_root_for("my_crate.sub.mod"), Path::new("/sub/root"));
+        assert_eq!(info.output_root_for("my_crate.other"), Path::new("/crate/root"));
+    }
 }